@@ -4,52 +4,207 @@
 //!
 //! - Merges multiple `/` into one.
 //! - Resolves and eliminates `..` and `.` if any.
-//! - Appends a trailing `/` if one is not present, and there is no file extension.
+//! - Depending on the configured [`TrailingSlash`] policy, appends or trims
+//!   the trailing `/`.
 //!
-//! It will respond with a permanent redirect if the path was cleaned.
+//! It will respond with a redirect if the path was cleaned, unless
+//! [`CleanPath::rewrite`] is configured, in which case the request's URL is
+//! rewritten in place and passed on to the next handler instead. The
+//! redirect status defaults to `308 Permanent Redirect`, which preserves
+//! the request method and body, and can be changed with
+//! [`CleanPath::status`].
+//!
+//! When a [`CleanPath::route_resolver`] is configured, the redirect (or
+//! rewrite) is only applied if the resolver confirms the normalized path
+//! actually resolves to a registered route; otherwise the request passes
+//! through unmodified, avoiding a redirect to what would just be a 404.
 //!
 //! ```rust
 //! # fn main() {
 //! let app = tide::new()
-//!     .middleware(tide_clean_path::CleanPath)
+//!     .middleware(tide_clean_path::CleanPath::new())
 //!     .at("/").get(|_| async { Ok("") });
 //! # }
 //! ```
 use std::future::Future;
 use std::pin::Pin;
-use tide::{Middleware, Next, Redirect, Request, Result};
+use std::sync::Arc;
+use tide::http::StatusCode;
+use tide::{Middleware, Next, Request, Result};
+
+/// Consulted by [`CleanPath`] to decide whether a normalized candidate path
+/// actually resolves to a registered route, given `true` if it does.
+///
+/// This is called synchronously from inside the middleware, so it must not
+/// block on `tide::Server`'s async request handling (tide also doesn't
+/// expose a public, synchronous "does this path match a route" API on
+/// `Server`/`Router` to call into anyway). Instead, build a plain
+/// synchronous lookup the app keeps in sync with its own routes, e.g. a
+/// `HashSet<String>` of registered paths, or a dedicated sync router crate
+/// (such as `matchit`) populated alongside `Server::at` calls.
+pub type RouteResolver = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Policy controlling how [`CleanPath`] treats a path's trailing `/`.
+pub enum TrailingSlash {
+    /// Append a trailing `/` whenever the path has no file extension. This
+    /// is the original, and default, behavior.
+    Always,
+    /// Strip trailing slashes, so `/a/b/` redirects to `/a/b`.
+    Trim,
+    /// Leave trailing slashes exactly as given; only merge `//` and resolve
+    /// `.`/`..`.
+    MergeOnly,
+}
+
+impl Default for TrailingSlash {
+    fn default() -> Self {
+        TrailingSlash::Always
+    }
+}
+
+pub struct CleanPath {
+    trailing_slash: TrailingSlash,
+    route_resolver: Option<RouteResolver>,
+    rewrite: bool,
+    status: StatusCode,
+}
+
+impl Default for CleanPath {
+    fn default() -> Self {
+        CleanPath {
+            trailing_slash: TrailingSlash::default(),
+            route_resolver: None,
+            rewrite: false,
+            status: StatusCode::PermanentRedirect,
+        }
+    }
+}
+
+impl CleanPath {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Configure the trailing-slash policy. Defaults to [`TrailingSlash::Always`].
+    ///
+    /// When [`CleanPath::route_resolver`] is also configured, this policy's
+    /// candidate is the one tried against the resolver first; it only falls
+    /// back to the other candidates if the preferred one doesn't resolve.
+    pub fn trailing_slash(mut self, trailing_slash: TrailingSlash) -> Self {
+        self.trailing_slash = trailing_slash;
+        self
+    }
+
+    /// Only redirect to a normalized candidate if `resolver` confirms it
+    /// actually resolves to a registered route, instead of redirecting
+    /// purely based on the path's shape. Candidates are tried with the
+    /// configured [`CleanPath::trailing_slash`] policy's preference first,
+    /// falling back to the other variants. If none of the candidates
+    /// resolve, the original request passes through unmodified.
+    pub fn route_resolver<F>(mut self, resolver: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.route_resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Clean the path in place, by rewriting the request's URL and passing
+    /// it on to `next`, instead of issuing a redirect back to the client.
+    /// Useful for internal services and API gateways where the extra
+    /// round-trip of a redirect is pure overhead.
+    pub fn rewrite(mut self) -> Self {
+        self.rewrite = true;
+        self
+    }
+
+    /// Configure the status code used for the redirect response. Defaults to
+    /// `308 Permanent Redirect`, which preserves the request method and
+    /// body. Use `301 Moved Permanently` for the classic redirect that user
+    /// agents are allowed to downgrade non-`GET` requests on, or `302`/`307`
+    /// for a temporary redirect. Has no effect when [`CleanPath::rewrite`]
+    /// is configured.
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
 
-pub struct CleanPath;
+    fn redirect(&self, req: &Request<impl Send + Sync + 'static>, path: &str) -> Result {
+        let mut new_url = req.url().clone();
+        new_url.set_path(path);
+        Ok(tide::Response::builder(self.status)
+            .header(tide::http::headers::LOCATION, new_url.as_str())
+            .build())
+    }
+}
 
 impl<State: Send + Sync + 'static> Middleware<State> for CleanPath {
     fn handle<'a>(
         &'a self,
-        req: Request<State>,
+        mut req: Request<State>,
         next: Next<'a, State>,
     ) -> Pin<Box<dyn Future<Output = Result> + Send + 'a>> {
         Box::pin(async move {
-            let original_path = req.url().path();
+            let original_path = req.url().path().to_string();
+
+            if let Some(resolver) = &self.route_resolver {
+                for candidate in route_candidates(&original_path, &self.trailing_slash) {
+                    if resolver(&candidate) {
+                        if candidate == original_path {
+                            break;
+                        }
+                        if self.rewrite {
+                            AsMut::<tide::http::Request>::as_mut(&mut req)
+                                .url_mut()
+                                .set_path(&candidate);
+                            return next.run(req).await;
+                        }
+                        return self.redirect(&req, &candidate);
+                    }
+                }
+                return next.run(req).await;
+            }
+
             let trailing_slash = original_path.ends_with('/');
 
             // non-allocating fast path
+            let wants_trailing_slash = match self.trailing_slash {
+                TrailingSlash::Always => !has_ext(&original_path),
+                TrailingSlash::Trim => false,
+                TrailingSlash::MergeOnly => trailing_slash,
+            };
             if !original_path.contains("/.")
                 && !original_path.contains("//")
-                && (has_ext(original_path) ^ trailing_slash)
+                && trailing_slash == wants_trailing_slash
             {
                 return next.run(req).await;
             }
 
-            let mut path = path_clean::clean(&original_path);
+            let mut path = clean_path(&original_path);
             if path != "/" {
-                if trailing_slash || !has_ext(&path) {
-                    path.push('/');
+                match self.trailing_slash {
+                    TrailingSlash::Always => {
+                        if trailing_slash || !has_ext(&path) {
+                            path.push('/');
+                        }
+                    }
+                    TrailingSlash::Trim => {}
+                    TrailingSlash::MergeOnly => {
+                        if trailing_slash {
+                            path.push('/');
+                        }
+                    }
                 }
             }
 
             if path != original_path {
-                let mut new_url = req.url().clone();
-                new_url.set_path(&path);
-                return Ok(Redirect::permanent(new_url).into());
+                if self.rewrite {
+                    AsMut::<tide::http::Request>::as_mut(&mut req)
+                        .url_mut()
+                        .set_path(&path);
+                    return next.run(req).await;
+                }
+                return self.redirect(&req, &path);
             }
 
             next.run(req).await
@@ -57,6 +212,66 @@ impl<State: Send + Sync + 'static> Middleware<State> for CleanPath {
     }
 }
 
+/// Builds the ordered set of normalization candidates tried by
+/// [`CleanPath::route_resolver`]: merge slashes/dots, merge and append a
+/// trailing slash, and just append a trailing slash to the original path.
+/// The candidate matching the configured `trailing_slash` policy is tried
+/// first, so a route_resolver doesn't silently override it; the other two
+/// remain as fallbacks in case the preferred candidate doesn't resolve.
+fn route_candidates(original_path: &str, trailing_slash: &TrailingSlash) -> Vec<String> {
+    let merged = clean_path(original_path);
+    let mut merged_with_slash = merged.clone();
+    if merged_with_slash != "/" && !merged_with_slash.ends_with('/') {
+        merged_with_slash.push('/');
+    }
+    let mut appended_slash = original_path.to_string();
+    if !appended_slash.ends_with('/') {
+        appended_slash.push('/');
+    }
+
+    let candidates = match trailing_slash {
+        TrailingSlash::Always if has_ext(&merged) && !original_path.ends_with('/') => {
+            vec![merged, merged_with_slash, appended_slash]
+        }
+        TrailingSlash::Always => vec![merged_with_slash, appended_slash, merged],
+        TrailingSlash::Trim => vec![merged, merged_with_slash, appended_slash],
+        TrailingSlash::MergeOnly if original_path.ends_with('/') => {
+            vec![merged_with_slash, merged, appended_slash]
+        }
+        TrailingSlash::MergeOnly => vec![merged, merged_with_slash, appended_slash],
+    };
+
+    let mut deduped: Vec<String> = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        if !deduped.contains(&candidate) {
+            deduped.push(candidate);
+        }
+    }
+    deduped
+}
+
+/// Resolves `.` and `..` segments in `path` against the root and merges
+/// repeated `/`, per RFC 3986 §5.2.4. No sequence of `..` can traverse above
+/// the root: tokenize on `/`, push normal segments onto a stack, pop on
+/// `..` (ignoring pops once the stack is empty), and drop `.` segments.
+fn clean_path(path: &str) -> String {
+    let mut stack: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                stack.pop();
+            }
+            segment => stack.push(segment),
+        }
+    }
+
+    let mut cleaned = String::with_capacity(path.len());
+    cleaned.push('/');
+    cleaned.push_str(&stack.join("/"));
+    cleaned
+}
+
 fn has_ext(path: &str) -> bool {
     path.rfind('.')
         .map(|index| {
@@ -73,7 +288,7 @@ mod tests {
 
     fn app() -> tide::Server<()> {
         let mut app = tide::new();
-        app.middleware(CleanPath);
+        app.middleware(CleanPath::new());
         app.at("/").get(|_| async { Ok("") });
         app.at("/*p").get(|_| async { Ok("") });
         app
@@ -83,10 +298,10 @@ mod tests {
     async fn test_clean() {
         let app = app();
         let cases = vec![
-            //("/.", "/"),
-            //("/..", "/"),
-            //("/..//..", "/"),
-            //("/./", "/"),
+            ("/.", "/"),
+            ("/..", "/"),
+            ("/..//..", "/"),
+            ("/./", "/"),
             ("//", "/"),
             ("///", "/"),
             ("///?a=1", "/?a=1"),
@@ -94,7 +309,8 @@ mod tests {
             ("//?a=1", "/?a=1"),
             ("//a//b//", "/a/b/"),
             ("//a//b//.", "/a/b/"),
-            // ("//a//b//../", "/a/"),
+            ("//a//b//../", "/a/"),
+            ("/a/../../../b", "/b/"),
             ("//a//b//./", "/a/b/"),
             ("//m.js", "/m.js"),
             ("/a//b", "/a/b/"),
@@ -132,4 +348,177 @@ mod tests {
             assert!(res.status().is_success(), "for {}", given);
         }
     }
+
+    #[async_std::test]
+    async fn test_trim_trailing_slash() {
+        let mut app = tide::new();
+        app.middleware(CleanPath::new().trailing_slash(super::TrailingSlash::Trim));
+        app.at("/").get(|_| async { Ok("") });
+        app.at("/*p").get(|_| async { Ok("") });
+
+        let cases = vec![("/a/b/", "/a/b"), ("/a//b//", "/a/b")];
+        for (given, clean) in cases.iter() {
+            let req = http::Request::new(
+                Method::Get,
+                Url::parse(&format!("http://localhost{}", given)).unwrap(),
+            );
+            let res: http::Response = app.respond(req).await.unwrap();
+            assert!(res.status().is_redirection(), "for {}", given);
+            assert_eq!(
+                &res.header(http::headers::LOCATION).unwrap().last().as_str(),
+                &format!("http://localhost{}", clean),
+                "for {}",
+                given,
+            );
+        }
+
+        let req = http::Request::new(
+            Method::Get,
+            Url::parse("http://localhost/a/b").unwrap(),
+        );
+        let res: http::Response = app.respond(req).await.unwrap();
+        assert!(res.status().is_success());
+    }
+
+    #[async_std::test]
+    async fn test_merge_only_trailing_slash() {
+        let mut app = tide::new();
+        app.middleware(CleanPath::new().trailing_slash(super::TrailingSlash::MergeOnly));
+        app.at("/").get(|_| async { Ok("") });
+        app.at("/*p").get(|_| async { Ok("") });
+
+        let cases = vec![("//a//b", "/a/b"), ("//a//b//", "/a/b/")];
+        for (given, clean) in cases.iter() {
+            let req = http::Request::new(
+                Method::Get,
+                Url::parse(&format!("http://localhost{}", given)).unwrap(),
+            );
+            let res: http::Response = app.respond(req).await.unwrap();
+            assert!(res.status().is_redirection(), "for {}", given);
+            assert_eq!(
+                &res.header(http::headers::LOCATION).unwrap().last().as_str(),
+                &format!("http://localhost{}", clean),
+                "for {}",
+                given,
+            );
+        }
+
+        for given in ["/a/b", "/a/b/"].iter() {
+            let req = http::Request::new(
+                Method::Get,
+                Url::parse(&format!("http://localhost{}", given)).unwrap(),
+            );
+            let res: http::Response = app.respond(req).await.unwrap();
+            assert!(res.status().is_success(), "for {}", given);
+        }
+    }
+
+    #[async_std::test]
+    async fn test_route_resolver() {
+        let known_routes = ["/", "/a/b/"];
+        let mut app = tide::new();
+        app.middleware(
+            CleanPath::new().route_resolver(move |path: &str| known_routes.contains(&path)),
+        );
+        app.at("/").get(|_| async { Ok("") });
+        app.at("/a/b/").get(|_| async { Ok("") });
+
+        // resolves once slashes are merged and a trailing slash is appended
+        let req = http::Request::new(
+            Method::Get,
+            Url::parse("http://localhost//a//b").unwrap(),
+        );
+        let res: http::Response = app.respond(req).await.unwrap();
+        assert!(res.status().is_redirection());
+        assert_eq!(
+            res.header(http::headers::LOCATION).unwrap().last().as_str(),
+            "http://localhost/a/b/",
+        );
+
+        // no candidate resolves to a registered route, so it passes through
+        let req = http::Request::new(
+            Method::Get,
+            Url::parse("http://localhost//nope").unwrap(),
+        );
+        let res: http::Response = app.respond(req).await.unwrap();
+        assert!(res.status().is_client_error());
+    }
+
+    #[async_std::test]
+    async fn test_route_resolver_prefers_trailing_slash_policy() {
+        // both "/a/b" and "/a/b/" resolve, so the configured Trim policy
+        // should win out over the resolver's default candidate order
+        let known_routes = ["/", "/a/b", "/a/b/"];
+        let mut app = tide::new();
+        app.middleware(
+            CleanPath::new()
+                .trailing_slash(super::TrailingSlash::Trim)
+                .route_resolver(move |path: &str| known_routes.contains(&path)),
+        );
+        app.at("/").get(|_| async { Ok("") });
+        app.at("/a/b").get(|_| async { Ok("") });
+        app.at("/a/b/").get(|_| async { Ok("") });
+
+        let req = http::Request::new(
+            Method::Get,
+            Url::parse("http://localhost//a//b//").unwrap(),
+        );
+        let res: http::Response = app.respond(req).await.unwrap();
+        assert!(res.status().is_redirection());
+        assert_eq!(
+            res.header(http::headers::LOCATION).unwrap().last().as_str(),
+            "http://localhost/a/b",
+        );
+    }
+
+    #[async_std::test]
+    async fn test_rewrite() {
+        let mut app = tide::new();
+        app.middleware(CleanPath::new().rewrite());
+        app.at("/a/b/").get(|req: tide::Request<()>| async move {
+            Ok(req.url().path().to_string())
+        });
+
+        let req = http::Request::new(
+            Method::Get,
+            Url::parse("http://localhost//a//b").unwrap(),
+        );
+        let mut res: http::Response = app.respond(req).await.unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(res.body_string().await.unwrap(), "/a/b/");
+    }
+
+    #[async_std::test]
+    async fn test_status() {
+        let mut app = tide::new();
+        app.middleware(CleanPath::new().status(http::StatusCode::MovedPermanently));
+        app.at("/*p").get(|_| async { Ok("") });
+
+        let req = http::Request::new(
+            Method::Post,
+            Url::parse("http://localhost//a//b").unwrap(),
+        );
+        let res: http::Response = app.respond(req).await.unwrap();
+        assert_eq!(res.status(), http::StatusCode::MovedPermanently);
+        assert_eq!(
+            res.header(http::headers::LOCATION).unwrap().last().as_str(),
+            "http://localhost/a/b/",
+        );
+    }
+
+    #[async_std::test]
+    async fn test_default_status() {
+        let mut app = tide::new();
+        app.middleware(CleanPath::new());
+        app.at("/*p").get(|_| async { Ok("") });
+
+        // defaults to a method/body-preserving 308, not a 301 that user
+        // agents may downgrade POST to GET on
+        let req = http::Request::new(
+            Method::Post,
+            Url::parse("http://localhost//a//b").unwrap(),
+        );
+        let res: http::Response = app.respond(req).await.unwrap();
+        assert_eq!(res.status(), http::StatusCode::PermanentRedirect);
+    }
 }